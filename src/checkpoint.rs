@@ -0,0 +1,156 @@
+//! Nested checkpoint/rollback journal for [`VulnerableVault`](crate::VulnerableVault).
+//!
+//! Modeled on the snapshot journals used by mutable-state engines: a
+//! [`CheckpointStack`] keeps a stack of journals, one per open checkpoint.
+//! `checkpoint()` pushes a new, empty journal. Every storage write made
+//! while a checkpoint is open should first call `record`, which captures
+//! the slot's pre-image the *first* time it is touched within that
+//! checkpoint (later writes to the same slot in the same checkpoint are
+//! no-ops for journaling purposes, since the first pre-image is the one
+//! that matters for a full rollback). `revert_to_checkpoint` pops the top
+//! journal and restores every captured pre-image, most-recent first.
+//! `commit_checkpoint` pops the top journal and folds its entries into the
+//! parent checkpoint (if any), so an outer checkpoint can still roll back
+//! state an inner, already-committed checkpoint touched.
+
+use stylus_sdk::alloy_primitives::{Address, U256};
+
+use crate::VulnerableVault;
+
+/// Identifies a single journaled storage slot.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JournalKey {
+    /// `balances[holder][asset_id]`.
+    Balance(Address, U256),
+    /// `total_deposits[asset_id]`.
+    TotalDeposits(U256),
+}
+
+impl JournalKey {
+    fn restore(self, vault: &mut VulnerableVault, old_value: U256) {
+        match self {
+            JournalKey::Balance(holder, asset_id) => {
+                vault
+                    .balances
+                    .setter(holder)
+                    .setter(asset_id)
+                    .set(old_value);
+            }
+            JournalKey::TotalDeposits(asset_id) => {
+                vault.total_deposits.setter(asset_id).set(old_value);
+            }
+        }
+    }
+}
+
+type Journal = Vec<(JournalKey, U256)>;
+
+/// A stack of nested journals recording pre-images of written storage slots.
+#[derive(Default)]
+pub struct CheckpointStack {
+    journals: Vec<Journal>,
+}
+
+impl CheckpointStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a new checkpoint; writes after this call are journaled against it.
+    pub fn checkpoint(&mut self) {
+        self.journals.push(Journal::new());
+    }
+
+    /// Records `old_value` as the pre-image of `balances[holder][asset_id]`,
+    /// unless that slot was already touched in the current checkpoint.
+    pub fn record_balance(&mut self, holder: Address, asset_id: U256, old_value: U256) {
+        self.record(JournalKey::Balance(holder, asset_id), old_value);
+    }
+
+    /// Records `old_value` as the pre-image of `total_deposits[asset_id]`,
+    /// unless that slot was already touched in the current checkpoint.
+    pub fn record_total_deposits(&mut self, asset_id: U256, old_value: U256) {
+        self.record(JournalKey::TotalDeposits(asset_id), old_value);
+    }
+
+    fn record(&mut self, key: JournalKey, old_value: U256) {
+        let Some(journal) = self.journals.last_mut() else {
+            return;
+        };
+        if !journal.iter().any(|(k, _)| *k == key) {
+            journal.push((key, old_value));
+        }
+    }
+
+    /// Pops the current checkpoint and restores every slot it touched to
+    /// its pre-image, most-recently-touched first.
+    pub fn revert_to_checkpoint(&mut self, vault: &mut VulnerableVault) {
+        let Some(journal) = self.journals.pop() else {
+            return;
+        };
+        for (key, old_value) in journal.into_iter().rev() {
+            key.restore(vault, old_value);
+        }
+    }
+
+    /// Pops the current checkpoint and folds it into the parent checkpoint
+    /// (if any), so the parent can still roll back this checkpoint's writes.
+    pub fn commit_checkpoint(&mut self) {
+        let Some(journal) = self.journals.pop() else {
+            return;
+        };
+        let Some(parent) = self.journals.last_mut() else {
+            return;
+        };
+        for entry in journal {
+            if !parent.iter().any(|(k, _)| k == &entry.0) {
+                parent.push(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_keeps_only_first_pre_image_per_slot() {
+        let mut stack = CheckpointStack::new();
+        stack.checkpoint();
+        stack.record_total_deposits(U256::from(0), U256::from(100));
+        stack.record_total_deposits(U256::from(0), U256::from(999)); // already touched, ignored
+        assert_eq!(stack.journals.len(), 1);
+        assert_eq!(stack.journals[0].len(), 1);
+        assert_eq!(stack.journals[0][0].1, U256::from(100));
+    }
+
+    #[test]
+    fn commit_merges_into_parent_for_later_rollback() {
+        let mut stack = CheckpointStack::new();
+        stack.checkpoint(); // outer
+        stack.record_total_deposits(U256::from(0), U256::from(100));
+        stack.checkpoint(); // inner
+        stack.record_total_deposits(U256::from(1), U256::from(200));
+        stack.commit_checkpoint(); // inner folds into outer
+
+        assert_eq!(stack.journals.len(), 1);
+        assert_eq!(stack.journals[0].len(), 2);
+    }
+
+    #[test]
+    fn nested_checkpoint_discard_leaves_outer_untouched() {
+        let mut stack = CheckpointStack::new();
+        stack.checkpoint(); // outer
+        stack.record_total_deposits(U256::from(0), U256::from(100));
+        stack.checkpoint(); // inner
+        stack.record_total_deposits(U256::from(1), U256::from(200));
+
+        // Discard the inner checkpoint's journal without merging (the
+        // caller chooses not to commit it), leaving only the outer one.
+        stack.journals.pop();
+
+        assert_eq!(stack.journals.len(), 1);
+        assert_eq!(stack.journals[0].len(), 1);
+    }
+}
@@ -0,0 +1,37 @@
+//! Typed errors for [`VulnerableVault`](crate::VulnerableVault).
+//!
+//! Guarded paths used to `assert!` and abort with an opaque revert string.
+//! `VaultError` gives callers structured, ABI-decodable revert data
+//! instead, following the usual Stylus practice of propagating state
+//! errors upward rather than panicking.
+
+use alloy_sol_types::sol;
+use stylus_sdk::prelude::*;
+
+sol! {
+    #[derive(Debug, PartialEq)]
+    error ReentrantCall();
+    #[derive(Debug, PartialEq)]
+    error InsufficientBalance(uint256 requested, uint256 available);
+    #[derive(Debug, PartialEq)]
+    error Overflow();
+    #[derive(Debug, PartialEq)]
+    error Underflow();
+    #[derive(Debug, PartialEq)]
+    error UnknownAsset(uint256 asset_id);
+    #[derive(Debug, PartialEq)]
+    error Unauthorized();
+    #[derive(Debug, PartialEq)]
+    error InsufficientAllowance(uint256 requested, uint256 available);
+}
+
+#[derive(SolidityError, Debug, PartialEq)]
+pub enum VaultError {
+    ReentrantCall(ReentrantCall),
+    InsufficientBalance(InsufficientBalance),
+    Overflow(Overflow),
+    Underflow(Underflow),
+    UnknownAsset(UnknownAsset),
+    Unauthorized(Unauthorized),
+    InsufficientAllowance(InsufficientAllowance),
+}
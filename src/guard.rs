@@ -0,0 +1,164 @@
+//! Reentrancy guards for [`VulnerableVault`](crate::VulnerableVault).
+//!
+//! [`TransientReentrancyGuard`] is an **experimental, opt-in** lock (behind
+//! the `transient-storage` feature) backed by TSTORE/TLOAD (EIP-1153 style
+//! transient storage), which is cleared automatically at the end of every
+//! transaction, so the set-then-reset pattern around a guarded call never
+//! touches persistent state and never pays the cold-SSTORE-then-refund cost
+//! that a persistent-storage lock does.
+//!
+//! **The host ABI below is unverified.** `raw` declares `tload_bytes32`/
+//! `tstore_bytes32` the same way `stylus_sdk::hostio` declares
+//! `storage_load_bytes32`/`storage_cache_bytes32`, against the same
+//! `vm_hooks` import module - but neither name appears anywhere in vendored
+//! `stylus-sdk` 0.10.8, and there is no confirmed evidence the Arbitrum
+//! Stylus host exposes transient storage under this or any ABI yet. Do not
+//! enable `transient-storage` in production until that's verified against
+//! your target chain's Stylus runtime; the persistent `reentrancy_status`
+//! guard in `lib.rs` is the default and the only one known to work today.
+//! There, the slot's original value is `0` at the start of every
+//! transaction, so a set-then-reset pair nets out to a no-op once the
+//! refund is accounted for, rather than two independent cold writes.
+
+use stylus_sdk::alloy_primitives::U256;
+use stylus_sdk::host::VM;
+
+use crate::errors::VaultError;
+
+/// Transient-storage slot holding the reentrancy lock (0 = free, 1 = held).
+const LOCK_SLOT: U256 = U256::ZERO;
+
+#[cfg(not(test))]
+mod raw {
+    //! Raw `TLOAD`/`TSTORE` host calls, declared the way
+    //! `stylus_sdk::hostio` declares `storage_load_bytes32`/
+    //! `storage_cache_bytes32` against the same `vm_hooks` import module.
+    //! Unverified - see the module-level warning above.
+    #[link(wasm_import_module = "vm_hooks")]
+    extern "C" {
+        pub(super) fn tload_bytes32(key: *const u8, dest: *mut u8);
+        pub(super) fn tstore_bytes32(key: *const u8, value: *const u8);
+    }
+}
+
+#[cfg(not(test))]
+fn tload(slot: U256) -> U256 {
+    use stylus_sdk::alloy_primitives::B256;
+    let key = B256::from(slot);
+    let mut dest = [0u8; 32];
+    unsafe { raw::tload_bytes32(key.as_ptr(), dest.as_mut_ptr()) };
+    U256::from_be_bytes(dest)
+}
+
+#[cfg(not(test))]
+fn tstore(slot: U256, value: U256) {
+    use stylus_sdk::alloy_primitives::B256;
+    let key = B256::from(slot);
+    let value = B256::from(value);
+    unsafe { raw::tstore_bytes32(key.as_ptr(), value.as_ptr()) };
+}
+
+// `cargo test` builds natively, where there is no wasm host to import
+// `tload_bytes32`/`tstore_bytes32` from. Emulate the same "cleared per
+// transaction" slot with a thread-local cell so the guard can still be
+// exercised end-to-end in tests; `cargo test` gives each `#[test]` its own
+// thread, so the slot starts fresh for every test the same way it would at
+// the start of every transaction on-chain.
+#[cfg(test)]
+std::thread_local! {
+    static NATIVE_TRANSIENT_SLOT: std::cell::Cell<U256> = const { std::cell::Cell::new(U256::ZERO) };
+}
+
+#[cfg(test)]
+fn tload(_slot: U256) -> U256 {
+    NATIVE_TRANSIENT_SLOT.with(|cell| cell.get())
+}
+
+#[cfg(test)]
+fn tstore(_slot: U256, value: U256) {
+    NATIVE_TRANSIENT_SLOT.with(|cell| cell.set(value));
+}
+
+/// RAII reentrancy lock backed by transient storage.
+///
+/// Acquire with [`TransientReentrancyGuard::enter`]; the lock is released
+/// when the guard is dropped at the end of the guarded call, however it
+/// returns. Because TSTORE/TLOAD are cleared at the end of every
+/// transaction, the lock can never leak across transactions even if a
+/// call errors out before the guard would otherwise go out of scope.
+pub struct TransientReentrancyGuard {
+    vm: VM,
+}
+
+impl TransientReentrancyGuard {
+    /// Acquires the lock, returning [`VaultError::ReentrantCall`] if it is
+    /// already held.
+    pub fn enter(vm: VM) -> Result<Self, VaultError> {
+        if tload(LOCK_SLOT) != U256::ZERO {
+            return Err(VaultError::ReentrantCall(crate::errors::ReentrantCall {}));
+        }
+        tstore(LOCK_SLOT, U256::from(1));
+        Ok(Self { vm })
+    }
+
+    /// Returns `true` if the lock is currently held.
+    pub fn is_entered(_vm: VM) -> bool {
+        tload(LOCK_SLOT) != U256::ZERO
+    }
+}
+
+impl Drop for TransientReentrancyGuard {
+    fn drop(&mut self) {
+        let _ = &self.vm;
+        tstore(LOCK_SLOT, U256::ZERO);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stylus_sdk::testing::TestVM;
+
+    // `TransientReentrancyGuard` only needs a `VM` to hold onto for `Drop`;
+    // the lock itself lives in the thread-local slot emulated above. Any
+    // `VM` will do, so wrap a fresh `TestVM` per test.
+    fn test_vm() -> VM {
+        VM {
+            host: alloc::boxed::Box::new(TestVM::new()),
+        }
+    }
+
+    #[test]
+    fn lock_slot_is_slot_zero() {
+        assert_eq!(LOCK_SLOT, U256::ZERO);
+    }
+
+    #[test]
+    fn starts_unlocked_and_enter_locks_it() {
+        let vm = test_vm();
+        assert!(!TransientReentrancyGuard::is_entered(vm.clone()));
+
+        let _guard = TransientReentrancyGuard::enter(vm.clone()).unwrap();
+        assert!(TransientReentrancyGuard::is_entered(vm.clone()));
+    }
+
+    #[test]
+    fn drop_releases_the_lock() {
+        let vm = test_vm();
+        {
+            let _guard = TransientReentrancyGuard::enter(vm.clone()).unwrap();
+            assert!(TransientReentrancyGuard::is_entered(vm.clone()));
+        }
+        assert!(!TransientReentrancyGuard::is_entered(vm.clone()));
+    }
+
+    #[test]
+    fn reentrant_enter_errors_while_held() {
+        let vm = test_vm();
+        let _outer = TransientReentrancyGuard::enter(vm.clone()).unwrap();
+        assert!(matches!(
+            TransientReentrancyGuard::enter(vm.clone()),
+            Err(VaultError::ReentrantCall(_))
+        ));
+    }
+}